@@ -0,0 +1,144 @@
+// Copyright (c) 2015, The Radare Project. All rights reserved.
+// See the COPYING file at the top-level directory of this distribution.
+// Licensed under the BSD 3-Clause License:
+// <http://opensource.org/licenses/BSD-3-Clause>
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Crate-wide error type returned by `R2` and `R2Pipe`.
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::io;
+
+use serde_json;
+
+/// Stable classification of an `R2Error`, for callers that want to branch
+/// on the failure category without matching every variant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum R2ErrorKind {
+    /// Failed to spawn r2 or attach to an existing r2pipe session.
+    Spawn,
+    /// Reading from or writing to the underlying pipe failed.
+    Io,
+    /// The data received from r2 could not be parsed as JSON.
+    Parse,
+    /// r2 rejected or otherwise failed to execute a command.
+    Command,
+    /// The r2pipe wire protocol itself misbehaved (e.g. a batched
+    /// request's response couldn't be demultiplexed), independent of
+    /// whether any individual command succeeded.
+    Protocol,
+}
+
+impl R2ErrorKind {
+    fn as_str(&self) -> &'static str {
+        match *self {
+            R2ErrorKind::Spawn => "Spawn",
+            R2ErrorKind::Io => "Io",
+            R2ErrorKind::Parse => "Parse",
+            R2ErrorKind::Command => "Command",
+            R2ErrorKind::Protocol => "Protocol",
+        }
+    }
+}
+
+impl fmt::Display for R2ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// The error type returned by every public method on `R2` and `R2Pipe`.
+#[derive(Debug)]
+pub enum R2Error {
+    /// Could not spawn r2 or find an open r2pipe session.
+    Spawn(String),
+    /// Low-level I/O failure while talking to the pipe.
+    Io(io::Error),
+    /// The response from r2 was not valid JSON (or did not match the
+    /// expected shape).
+    Parse(serde_json::Error),
+    /// r2 itself reported that a command failed.
+    Command(String),
+    /// The r2pipe wire protocol misbehaved, e.g. a batched response could
+    /// not be split back into its individual commands.
+    Protocol(String),
+}
+
+impl R2Error {
+    /// A stable, coarse classification of this error, e.g. for logging or
+    /// deciding whether a retry makes sense.
+    pub fn kind(&self) -> &'static str {
+        match *self {
+            R2Error::Spawn(_) => R2ErrorKind::Spawn.as_str(),
+            R2Error::Io(_) => R2ErrorKind::Io.as_str(),
+            R2Error::Parse(_) => R2ErrorKind::Parse.as_str(),
+            R2Error::Command(_) => R2ErrorKind::Command.as_str(),
+            R2Error::Protocol(_) => R2ErrorKind::Protocol.as_str(),
+        }
+    }
+}
+
+impl fmt::Display for R2Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            R2Error::Spawn(ref msg) => write!(f, "failed to spawn r2: {}", msg),
+            R2Error::Io(ref e) => write!(f, "r2pipe I/O error: {}", e),
+            R2Error::Parse(ref e) => write!(f, "failed to parse r2 output: {}", e),
+            R2Error::Command(ref msg) => write!(f, "r2 command failed: {}", msg),
+            R2Error::Protocol(ref msg) => write!(f, "r2pipe protocol error: {}", msg),
+        }
+    }
+}
+
+impl StdError for R2Error {
+    fn source(&self) -> Option<&(StdError + 'static)> {
+        match *self {
+            R2Error::Io(ref e) => Some(e),
+            R2Error::Parse(ref e) => Some(e),
+            R2Error::Spawn(_) | R2Error::Command(_) | R2Error::Protocol(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for R2Error {
+    fn from(e: io::Error) -> R2Error {
+        R2Error::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for R2Error {
+    fn from(e: serde_json::Error) -> R2Error {
+        R2Error::Parse(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error as StdError;
+    use std::io;
+
+    #[test]
+    fn kind_matches_variant() {
+        assert_eq!(R2Error::Spawn("x".to_owned()).kind(), "Spawn");
+        assert_eq!(R2Error::Command("x".to_owned()).kind(), "Command");
+        assert_eq!(R2Error::Protocol("x".to_owned()).kind(), "Protocol");
+    }
+
+    #[test]
+    fn io_error_is_wrapped_and_exposed_as_source() {
+        let io_err = io::Error::new(io::ErrorKind::Other, "boom");
+        let err: R2Error = io_err.into();
+        assert_eq!(err.kind(), "Io");
+        assert!(err.source().is_some());
+    }
+
+    #[test]
+    fn spawn_and_command_have_no_source() {
+        assert!(R2Error::Spawn("x".to_owned()).source().is_none());
+        assert!(R2Error::Command("x".to_owned()).source().is_none());
+        assert!(R2Error::Protocol("x".to_owned()).source().is_none());
+    }
+}
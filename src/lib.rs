@@ -48,12 +48,19 @@
 
 extern crate libc;
 extern crate rustc_serialize;
+extern crate futures;
 
 #[macro_use]
 pub mod r2pipe;
+pub mod error;
 pub mod r2;
+pub mod r2_async;
 pub mod structs;
+pub mod version;
 
 // Rexport to bring it out one module.
 pub use self::r2pipe::R2Pipe;
+pub use self::error::R2Error;
 pub use self::r2::R2;
+pub use self::r2_async::{R2Async, R2Future};
+pub use self::version::{Capability, R2Version};
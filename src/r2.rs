@@ -20,11 +20,14 @@
 //! feel free to raise an issue, or better yet a pull request implementing the
 //! same.
 
+use std::collections::HashSet;
+
 use r2pipe::R2Pipe;
 use serde_json;
-use serde_json::Error;
 use serde_json::Value;
 
+use error::R2Error;
+use version::{self, Capability, R2Version};
 use super::structs::*;
 
 mod t_structs {
@@ -66,6 +69,8 @@ mod t_structs {
 pub struct R2 {
     pipe: R2Pipe,
     readin: String,
+    version: R2Version,
+    caps: HashSet<Capability>,
 }
 
 impl Default for R2 {
@@ -78,18 +83,19 @@ impl Default for R2 {
 // i.e. The ones that are not currently abstracted by the R2 API.
 // Ideally, all commonly used commands must be supported for easier use.
 impl R2 {
-    // TODO: Use an error type
-    pub fn new<T: AsRef<str>>(path: Option<T>) -> Result<R2, String> {
+    pub fn new<T: AsRef<str>>(path: Option<T>) -> Result<R2, R2Error> {
         if path.is_none() && !R2::in_session() {
             let e = "No r2 session open. Please specify path!".to_owned();
-            return Err(e);
+            return Err(R2Error::Spawn(e));
         }
 
         // This means that path is `Some` or we have an open session.
-        let pipe = open_pipe!(path.as_ref()).unwrap();
+        let pipe = open_pipe!(path.as_ref()).map_err(R2Error::Spawn)?;
         Ok(R2 {
             pipe: pipe,
             readin: String::new(),
+            version: R2Version::default(),
+            caps: HashSet::new(),
         })
     }
 
@@ -104,22 +110,72 @@ impl R2 {
         R2 {
             pipe: r2p,
             readin: String::new(),
+            version: R2Version::default(),
+            caps: HashSet::new(),
         }
     }
 
+    /// The version of the r2 this is talking to, as detected by `init()`.
+    /// Reads as `0.0.0` until `init()` has run.
+    pub fn version(&self) -> R2Version {
+        self.version
+    }
+
+    /// Whether the connected r2 was detected to support `cap`. Always
+    /// `false` until `init()` has run.
+    pub fn has(&self, cap: Capability) -> bool {
+        self.caps.contains(&cap)
+    }
+
+    // Probe `?Vj` and `ij` once and cache the resulting version/capability
+    // set, so `init()` and the typed wrappers can pick the right command
+    // variant instead of guessing.
+    fn probe(&mut self) -> Result<(), R2Error> {
+        self.send("?Vj")?;
+        let raw = self.recv();
+        let version_json = if raw.trim().is_empty() {
+            Value::Null
+        } else {
+            serde_json::from_str(&raw).unwrap_or(Value::Null)
+        };
+        self.version = R2Version::from_json(&version_json);
+
+        self.send("ij")?;
+        let raw = self.recv();
+        let info: Value = if raw.trim().is_empty() {
+            Value::Null
+        } else {
+            serde_json::from_str(&raw)?
+        };
+        self.caps = version::detect(self.version, &info);
+        Ok(())
+    }
+
     // Does some basic configurations (sane defaults).
-    pub fn init(&mut self) {
-        self.send("e asm.esil = true");
-        self.send("e scr.color = false");
-        self.analyze();
+    pub fn init(&mut self) -> Result<(), R2Error> {
+        self.probe()?;
+
+        if self.has(Capability::Esil) {
+            self.send("e asm.esil = true")?;
+        }
+        self.send("e scr.color = false")?;
+
+        if self.has(Capability::BinLoaded) {
+            self.send("aaa")?;
+            self.flush();
+            Ok(())
+        } else {
+            self.analyze()
+        }
     }
 
     pub fn close(&mut self) {
-        self.send("q!");
+        let _ = self.send("q!");
     }
 
-    pub fn send(&mut self, cmd: &str) {
-        self.readin = self.pipe.cmd(cmd).unwrap();
+    pub fn send(&mut self, cmd: &str) -> Result<(), R2Error> {
+        self.readin = self.pipe.cmd(cmd)?;
+        Ok(())
     }
 
     pub fn recv(&mut self) -> String {
@@ -128,103 +184,239 @@ impl R2 {
         res
     }
 
-    pub fn recv_json(&mut self) -> Value {
+    pub fn recv_json(&mut self) -> Result<Value, R2Error> {
         let mut res = self.recv().replace("\n", "");
         if res.is_empty() {
             res = "{}".to_owned();
         }
 
-        // TODO: this should probably return a Result<Value, Error>
-        serde_json::from_str(&res).unwrap()
+        Ok(serde_json::from_str(&res)?)
     }
 
     pub fn flush(&mut self) {
         self.readin = String::from("");
     }
 
-    pub fn analyze(&mut self) {
-        self.send("aa");
+    pub fn analyze(&mut self) -> Result<(), R2Error> {
+        self.send("aa")?;
         self.flush();
+        Ok(())
     }
 
-    pub fn function(&mut self, func: &str) -> Result<LFunctionInfo, Error> {
+    pub fn function(&mut self, func: &str) -> Result<LFunctionInfo, R2Error> {
         let cmd = format!("pdfj @ {}", func);
-        self.send(&cmd);
+        self.send(&cmd)?;
         let raw_json = self.recv();
-        // Handle Error here.
-        serde_json::from_str(&raw_json)
+        Ok(serde_json::from_str(&raw_json)?)
     }
 
     // get 'n' (or 16) instructions at 'offset' (or current position if offset in
     // `None`)
-    pub fn insts(&mut self, n: Option<u64>, offset: Option<&str>) -> Result<Vec<LOpInfo>, Error> {
+    pub fn insts(&mut self,
+                 n: Option<u64>,
+                 offset: Option<&str>)
+                 -> Result<Vec<LOpInfo>, R2Error> {
         let n = n.unwrap_or(16);
         let offset: &str = offset.unwrap_or_default();
         let mut cmd = format!("pdj{}", n);
         if !offset.is_empty() {
             cmd = format!("{} @ {}", cmd, offset);
         }
-        self.send(&cmd);
+        self.send(&cmd)?;
         let raw_json = self.recv();
-        serde_json::from_str(&raw_json)
+        Ok(serde_json::from_str(&raw_json)?)
     }
 
-    pub fn reg_info(&mut self) -> Result<LRegInfo, Error> {
-        self.send("drpj");
+    pub fn reg_info(&mut self) -> Result<LRegInfo, R2Error> {
+        self.send("drpj")?;
         let raw_json = self.recv();
-        serde_json::from_str(&raw_json)
+        Ok(serde_json::from_str(&raw_json)?)
     }
 
-    pub fn flag_info(&mut self) -> Result<Vec<LFlagInfo>, Error> {
-        self.send("fj");
+    pub fn flag_info(&mut self) -> Result<Vec<LFlagInfo>, R2Error> {
+        self.send("fj")?;
         let raw_json = self.recv();
-        serde_json::from_str(&raw_json)
+        Ok(serde_json::from_str(&raw_json)?)
     }
 
-    pub fn bin_info(&mut self) -> Result<LBinInfo, Error> {
-        self.send("ij");
+    pub fn bin_info(&mut self) -> Result<LBinInfo, R2Error> {
+        self.send("ij")?;
         let raw_json = self.recv();
-        serde_json::from_str(&raw_json)
+        Ok(serde_json::from_str(&raw_json)?)
     }
 
-    pub fn fn_list(&mut self) -> Result<Vec<FunctionInfo>, Error> {
-        self.send("aflj");
+    pub fn fn_list(&mut self) -> Result<Vec<FunctionInfo>, R2Error> {
+        self.send("aflj")?;
         let raw_json = self.recv();
-        let mut finfo: Result<Vec<FunctionInfo>, Error> =
+        let mut finfo: Vec<FunctionInfo> =
             serde_json::from_str::<Vec<t_structs::FunctionInfo_>>(&raw_json)
-                .map(|x| x.iter().map(From::from).collect());
-        if let Ok(ref mut fns) = finfo {
-            for f in fns.iter_mut() {
-                let res = self.locals_of(f.offset.unwrap());
-                if res.is_ok() {
-                    f.locals = res.ok();
-                } else {
-                    f.locals = Some(Vec::new());
+                .map(|x| x.iter().map(From::from).collect())?;
+
+        // Fetch every function's locals in a single round trip instead of
+        // one `afvbj` per function. A function whose locals are missing
+        // from the batched reply or can't be parsed just falls back to an
+        // empty list, the same way a failed per-function `locals_of` call
+        // used to. Only a failure of the batch's pipe round trip itself
+        // fails the whole list.
+        let local_cmds: Vec<String> = finfo.iter()
+            .map(|f| format!("afvbj @ {}", f.offset.unwrap()))
+            .collect();
+        let local_cmds: Vec<&str> = local_cmds.iter().map(String::as_str).collect();
+        let locals = self.cmd_batch(&local_cmds)?;
+        for (f, raw) in finfo.iter_mut().zip(locals) {
+            f.locals = match raw {
+                Ok(Value::Null) => Some(Vec::new()),
+                Ok(value) => {
+                    serde_json::from_value::<Vec<LVarInfo>>(value)
+                        .map(Some)
+                        .unwrap_or_else(|_| Some(Vec::new()))
                 }
-            }
+                Err(_) => Some(Vec::new()),
+            };
+        }
+        Ok(finfo)
+    }
+
+    /// Run several commands in a single pipe round trip.
+    ///
+    /// The commands are joined with `\n` (r2 executes a newline-separated
+    /// command list as a batch) with a `?e BATCH_MARKER<idx>` marker
+    /// command interleaved after each one. Since a command's own output
+    /// may be empty, span multiple lines, or itself be JSON, the markers
+    /// are the only reliable way to tell where one command's reply ends
+    /// and the next begins.
+    ///
+    /// The outer `Result` only reports pipe-level failure (e.g. the write
+    /// itself failing); each command's own result is reported
+    /// independently, so one unparsable or desynced segment doesn't lose
+    /// the rest of the batch. Once a marker goes missing the remaining
+    /// segments can no longer be located reliably, so everything from that
+    /// point on is reported as an error too.
+    pub fn cmd_batch(&mut self, cmds: &[&str]) -> Result<Vec<Result<Value, R2Error>>, R2Error> {
+        if cmds.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut batch = String::new();
+        for (i, cmd) in cmds.iter().enumerate() {
+            batch.push_str(cmd);
+            batch.push('\n');
+            batch.push_str(&format!("?e {}{}\n", BATCH_MARKER, i));
         }
-        finfo
+        self.send(&batch)?;
+        let raw = self.recv();
+        Ok(split_batch_response(&raw, cmds.len()))
     }
 
-    pub fn sections(&mut self) -> Result<Vec<LSectionInfo>, Error> {
-        self.send("Sj");
-        serde_json::from_str(&self.recv())
+    pub fn sections(&mut self) -> Result<Vec<LSectionInfo>, R2Error> {
+        self.send("Sj")?;
+        Ok(serde_json::from_str(&self.recv())?)
     }
 
-    pub fn strings(&mut self, data_only: bool) -> Result<Vec<LStringInfo>, Error> {
-        if data_only {
-            self.send("izj");
-            serde_json::from_str(&self.recv())
+    pub fn strings(&mut self, data_only: bool) -> Result<Vec<LStringInfo>, R2Error> {
+        // `izzj` (data + code strings) only exists on newer r2; fall back
+        // to the data-only `izj` on builds that predate it.
+        if data_only || !self.has(Capability::AllStringsJson) {
+            self.send("izj")?;
         } else {
-            self.send("izzj");
-            let x: Result<Vec<LStringInfo>, Error> = serde_json::from_str(&self.recv());
-            x
+            self.send("izzj")?;
         }
+        Ok(serde_json::from_str(&self.recv())?)
+    }
+
+    pub fn locals_of(&mut self, location: u64) -> Result<Vec<LVarInfo>, R2Error> {
+        self.send(&format!("afvbj @ {}", location))?;
+        Ok(serde_json::from_str(&self.recv())?)
+    }
+}
+
+// Printable and collision-safe: unlike an embedded NUL byte, this can't be
+// mistaken by the transport (or `?e`'s own argument tokenizing) for an
+// early end-of-message, since it contains no control bytes at all.
+const BATCH_MARKER: &'static str = "R2PIPE_RS_BATCH_MARKER_";
+
+// Split the aggregated reply of a `cmd_batch` call into one `Result` per
+// command. Pure and independent of any live pipe, so it can be exercised
+// directly with a table of `(raw response) -> (expected segments)` cases.
+fn split_batch_response(raw: &str, n: usize) -> Vec<Result<Value, R2Error>> {
+    let mut results = Vec::with_capacity(n);
+    let mut rest = raw;
+    let mut desynced = false;
+
+    for i in 0..n {
+        if desynced {
+            let msg = format!("batch response desynced before command {}", i);
+            results.push(Err(R2Error::Protocol(msg)));
+            continue;
+        }
+
+        let marker = format!("{}{}", BATCH_MARKER, i);
+        match rest.find(marker.as_str()) {
+            Some(pos) => {
+                let segment = rest[..pos].trim();
+                results.push(if segment.is_empty() {
+                    Ok(Value::Null)
+                } else {
+                    serde_json::from_str(segment).map_err(R2Error::from)
+                });
+                rest = &rest[pos + marker.len()..];
+            }
+            None => {
+                let msg = format!("missing batch marker for command {}", i);
+                results.push(Err(R2Error::Protocol(msg)));
+                desynced = true;
+            }
+        }
+    }
+
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn marker(i: usize) -> String {
+        format!("{}{}", BATCH_MARKER, i)
+    }
+
+    #[test]
+    fn splits_plain_values() {
+        let raw = format!("1{}\"foo\"{}", marker(0), marker(1));
+        let results = split_batch_response(&raw, 2);
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].as_ref().unwrap(), &Value::from(1));
+        assert_eq!(results[1].as_ref().unwrap(), &Value::from("foo"));
+    }
+
+    #[test]
+    fn empty_segment_becomes_null() {
+        let raw = format!("{}[1,2]{}", marker(0), marker(1));
+        let results = split_batch_response(&raw, 2);
+        assert_eq!(results[0].as_ref().unwrap(), &Value::Null);
+        assert_eq!(results[1].as_ref().unwrap(), &Value::from(vec![1, 2]));
+    }
+
+    #[test]
+    fn unparsable_segment_is_its_own_error_not_a_whole_batch_failure() {
+        let raw = format!("not json{}[1]{}", marker(0), marker(1));
+        let results = split_batch_response(&raw, 2);
+        assert!(results[0].is_err());
+        assert_eq!(results[1].as_ref().unwrap(), &Value::from(vec![1]));
+    }
+
+    #[test]
+    fn missing_marker_desyncs_only_from_that_point_on() {
+        let raw = format!("1{}", marker(0));
+        let results = split_batch_response(&raw, 3);
+        assert_eq!(results[0].as_ref().unwrap(), &Value::from(1));
+        assert!(results[1].is_err());
+        assert!(results[2].is_err());
     }
 
-    pub fn locals_of(&mut self, location: u64) -> Result<Vec<LVarInfo>, Error> {
-        self.send(&format!("afvbj @ {}", location));
-        let x: Result<Vec<LVarInfo>, Error> = serde_json::from_str(&self.recv());
-        x
+    #[test]
+    fn empty_command_list_yields_no_segments() {
+        let results = split_batch_response("", 0);
+        assert!(results.is_empty());
     }
 }
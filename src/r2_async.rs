@@ -0,0 +1,193 @@
+// Copyright (c) 2015, The Radare Project. All rights reserved.
+// See the COPYING file at the top-level directory of this distribution.
+// Licensed under the BSD 3-Clause License:
+// <http://opensource.org/licenses/BSD-3-Clause>
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! `R2Async`, an async-friendly cousin of `R2`.
+//!
+//! Moves a plain `R2` onto a dedicated worker thread and exposes its
+//! typed methods as futures, so callers can pipeline several requests
+//! (e.g. analysing many functions) without blocking.
+
+use std::sync::mpsc;
+use std::thread;
+
+use futures::sync::oneshot;
+use futures::{Async, Future, Poll};
+
+use error::R2Error;
+use r2::R2;
+use structs::*;
+
+type Job = Box<FnMut(&mut R2) + Send>;
+
+/// A future resolving to the typed result of a single request made through
+/// `R2Async`.
+pub struct R2Future<T> {
+    inner: oneshot::Receiver<Result<T, R2Error>>,
+}
+
+impl<T> Future for R2Future<T> {
+    type Item = T;
+    type Error = R2Error;
+
+    fn poll(&mut self) -> Poll<T, R2Error> {
+        match self.inner.poll() {
+            Ok(Async::Ready(res)) => res.map(Async::Ready),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(_) => {
+                let msg = "r2 worker thread went away before replying".to_owned();
+                Err(R2Error::Spawn(msg))
+            }
+        }
+    }
+}
+
+/// An `R2` that runs on a background worker thread, exposing its methods
+/// as futures instead of blocking calls.
+pub struct R2Async {
+    jobs: mpsc::Sender<Job>,
+}
+
+impl R2Async {
+    /// Spawn r2 (or attach to an open session, same rules as `R2::new`) on
+    /// a dedicated worker thread and hand back a handle to it.
+    pub fn spawn<T>(path: Option<T>) -> Result<R2Async, R2Error>
+        where T: AsRef<str> + Send + 'static
+    {
+        let (jobs_tx, jobs_rx) = mpsc::channel::<Job>();
+        let (ready_tx, ready_rx) = mpsc::channel::<Result<(), R2Error>>();
+
+        thread::spawn(move || match R2::new(path) {
+            Ok(mut r2) => {
+                let _ = ready_tx.send(Ok(()));
+                for mut job in jobs_rx {
+                    job(&mut r2);
+                }
+            }
+            Err(e) => {
+                let _ = ready_tx.send(Err(e));
+            }
+        });
+
+        ready_rx
+            .recv()
+            .unwrap_or_else(|_| {
+                let msg = "r2 worker thread died before it could start".to_owned();
+                Err(R2Error::Spawn(msg))
+            })?;
+
+        Ok(R2Async { jobs: jobs_tx })
+    }
+
+    // Ship a request off to the worker thread and return a future for its
+    // typed result. `f` runs on the worker and has exclusive access to the
+    // wrapped `R2`, so the blocking `R2` methods can be reused as-is.
+    fn request<T, F>(&self, f: F) -> R2Future<T>
+        where T: Send + 'static,
+              F: FnOnce(&mut R2) -> Result<T, R2Error> + Send + 'static
+    {
+        let (tx, rx) = oneshot::channel();
+        let mut f = Some(f);
+        let job: Job = Box::new(move |r2: &mut R2| {
+            if let Some(f) = f.take() {
+                let _ = tx.send(f(r2));
+            }
+        });
+        // The worker only goes away if it panicked, in which case the send
+        // fails and drops the job along with `tx`, which surfaces as an
+        // error when the future is polled.
+        let _ = self.jobs.send(job);
+        R2Future { inner: rx }
+    }
+
+    pub fn function(&self, func: &str) -> R2Future<LFunctionInfo> {
+        let func = func.to_owned();
+        self.request(move |r2| r2.function(&func))
+    }
+
+    pub fn insts(&self, n: Option<u64>, offset: Option<&str>) -> R2Future<Vec<LOpInfo>> {
+        let offset = offset.map(|s| s.to_owned());
+        self.request(move |r2| r2.insts(n, offset.as_ref().map(|s| s.as_str())))
+    }
+
+    pub fn reg_info(&self) -> R2Future<LRegInfo> {
+        self.request(|r2| r2.reg_info())
+    }
+
+    pub fn flag_info(&self) -> R2Future<Vec<LFlagInfo>> {
+        self.request(|r2| r2.flag_info())
+    }
+
+    pub fn bin_info(&self) -> R2Future<LBinInfo> {
+        self.request(|r2| r2.bin_info())
+    }
+
+    pub fn fn_list(&self) -> R2Future<Vec<FunctionInfo>> {
+        self.request(|r2| r2.fn_list())
+    }
+
+    pub fn sections(&self) -> R2Future<Vec<LSectionInfo>> {
+        self.request(|r2| r2.sections())
+    }
+
+    pub fn strings(&self, data_only: bool) -> R2Future<Vec<LStringInfo>> {
+        self.request(move |r2| r2.strings(data_only))
+    }
+
+    pub fn locals_of(&self, location: u64) -> R2Future<Vec<LVarInfo>> {
+        self.request(move |r2| r2.locals_of(location))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn poll_surfaces_a_sent_value() {
+        let (tx, rx) = oneshot::channel();
+        tx.send(Ok(42)).unwrap();
+        let mut fut: R2Future<i32> = R2Future { inner: rx };
+        match fut.poll() {
+            Ok(Async::Ready(v)) => assert_eq!(v, 42),
+            other => panic!("expected Ready(42), got {:?}", other.is_ok()),
+        }
+    }
+
+    #[test]
+    fn poll_surfaces_a_sent_error() {
+        let (tx, rx) = oneshot::channel::<Result<i32, R2Error>>();
+        tx.send(Err(R2Error::Command("nope".to_owned()))).unwrap();
+        let mut fut: R2Future<i32> = R2Future { inner: rx };
+        match fut.poll() {
+            Err(R2Error::Command(ref msg)) => assert_eq!(msg, "nope"),
+            other => panic!("expected Command error, got {:?}", other.is_ok()),
+        }
+    }
+
+    #[test]
+    fn poll_is_not_ready_before_the_worker_replies() {
+        let (_tx, rx) = oneshot::channel::<Result<i32, R2Error>>();
+        let mut fut: R2Future<i32> = R2Future { inner: rx };
+        // A pending oneshot registers the current task, so poll inside one.
+        let polled = ::futures::future::lazy(move || Ok::<_, ()>(fut.poll())).wait().unwrap();
+        match polled {
+            Ok(Async::NotReady) => {}
+            other => panic!("expected NotReady, got {:?}", other.is_ok()),
+        }
+    }
+
+    #[test]
+    fn poll_errors_out_when_the_sender_is_dropped() {
+        let (tx, rx) = oneshot::channel::<Result<i32, R2Error>>();
+        drop(tx);
+        let mut fut: R2Future<i32> = R2Future { inner: rx };
+        match fut.poll() {
+            Err(R2Error::Spawn(_)) => {}
+            other => panic!("expected Spawn error, got {:?}", other.is_ok()),
+        }
+    }
+}
@@ -0,0 +1,139 @@
+// Copyright (c) 2015, The Radare Project. All rights reserved.
+// See the COPYING file at the top-level directory of this distribution.
+// Licensed under the BSD 3-Clause License:
+// <http://opensource.org/licenses/BSD-3-Clause>
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Version and capability detection for the r2 instance an `R2` is talking
+//! to.
+
+use std::collections::HashSet;
+
+use serde_json::Value;
+
+/// A parsed `major.minor.patch` r2 version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct R2Version {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl R2Version {
+    /// True if this version is `>=` the given `major.minor.patch`.
+    pub fn at_least(&self, major: u32, minor: u32, patch: u32) -> bool {
+        (self.major, self.minor, self.patch) >= (major, minor, patch)
+    }
+
+    fn parse(raw: &str) -> R2Version {
+        // Versions are sometimes suffixed, e.g. "3.7.0-git".
+        let raw = raw.trim().split('-').next().unwrap_or(raw).trim();
+        let mut parts = raw.split('.').map(|p| p.parse::<u32>().unwrap_or(0));
+        R2Version {
+            major: parts.next().unwrap_or(0),
+            minor: parts.next().unwrap_or(0),
+            patch: parts.next().unwrap_or(0),
+        }
+    }
+
+    /// Build an `R2Version` from the parsed `?Vj` reply, which may be a
+    /// plain version string or an object with `major`/`minor`/`patch`
+    /// fields depending on the r2 release.
+    pub(crate) fn from_json(v: &Value) -> R2Version {
+        if let Some(s) = v.as_str() {
+            return R2Version::parse(s);
+        }
+        R2Version {
+            major: v.get("major").and_then(Value::as_u64).unwrap_or(0) as u32,
+            minor: v.get("minor").and_then(Value::as_u64).unwrap_or(0) as u32,
+            patch: v.get("patch").and_then(Value::as_u64).unwrap_or(0) as u32,
+        }
+    }
+}
+
+/// A feature that may or may not be available on the connected r2,
+/// detected once at `init()` time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Capability {
+    /// `e asm.esil` exists and can be toggled (r2 >= 0.9.0; ESIL predates
+    /// every release this crate otherwise supports, but a `?Vj` that
+    /// fails to parse reports version `0.0.0` and should not assume it).
+    Esil,
+    /// A binary is actually loaded (as opposed to a bare core with no
+    /// file). `init()` runs the full `aaa` analysis only when this is set,
+    /// and plain `aa` otherwise.
+    BinLoaded,
+    /// `izzj` (combined data + code strings, JSON) is available (r2 >=
+    /// 0.9.8); older builds only have the data-only `izj`.
+    AllStringsJson,
+}
+
+/// Derive the capability set for a given version and parsed `ij` reply.
+pub(crate) fn detect(version: R2Version, info: &Value) -> HashSet<Capability> {
+    let mut caps = HashSet::new();
+
+    if version.at_least(0, 9, 0) {
+        caps.insert(Capability::Esil);
+    }
+    if version.at_least(0, 9, 8) {
+        caps.insert(Capability::AllStringsJson);
+    }
+
+    let bin_loaded = info.get("bin").map_or(false, |b| !b.is_null());
+    if bin_loaded {
+        caps.insert(Capability::BinLoaded);
+    }
+
+    caps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    fn parse(raw: &str) -> Value {
+        serde_json::from_str(raw).unwrap()
+    }
+
+    #[test]
+    fn parses_plain_version_string() {
+        let v = R2Version::from_json(&parse("\"3.7.0-git\""));
+        assert_eq!(v, R2Version { major: 3, minor: 7, patch: 0 });
+    }
+
+    #[test]
+    fn parses_structured_version_object() {
+        let v = R2Version::from_json(&parse(r#"{"major":1,"minor":2,"patch":3}"#));
+        assert_eq!(v, R2Version { major: 1, minor: 2, patch: 3 });
+    }
+
+    #[test]
+    fn unparsable_version_defaults_to_zero() {
+        let v = R2Version::from_json(&Value::Null);
+        assert_eq!(v, R2Version::default());
+        assert!(!v.at_least(0, 1, 0));
+    }
+
+    #[test]
+    fn old_version_lacks_new_capabilities() {
+        let v = R2Version { major: 0, minor: 9, patch: 5 };
+        let caps = detect(v, &Value::Null);
+        assert!(caps.contains(&Capability::Esil));
+        assert!(!caps.contains(&Capability::AllStringsJson));
+    }
+
+    #[test]
+    fn bin_loaded_is_detected_from_ij() {
+        let v = R2Version::default();
+        let with_bin = detect(v, &parse(r#"{"bin":{"arch":"x86"}}"#));
+        assert!(with_bin.contains(&Capability::BinLoaded));
+
+        let without_bin = detect(v, &parse(r#"{"bin":null}"#));
+        assert!(!without_bin.contains(&Capability::BinLoaded));
+
+        let no_bin_field = detect(v, &parse(r#"{"core":{}}"#));
+        assert!(!no_bin_field.contains(&Capability::BinLoaded));
+    }
+}